@@ -0,0 +1,50 @@
+use std::fs::File;
+use std::path::Path;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+/// A single (encoder, input, speed level) timing sample, as written by
+/// `--save-baseline` and matched against by `--baseline`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub kind: String,
+    pub version: String,
+    pub input: String,
+    pub level: String,
+    pub mean: f64,
+    pub stddev: f64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Baseline {
+    pub entries: Vec<Entry>,
+}
+
+impl Baseline {
+    pub fn load(path: &Path) -> Result<Self> {
+        let f = File::open(path)?;
+        Ok(serde_json::from_reader(f)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let f = File::create(path)?;
+        serde_json::to_writer_pretty(f, self)?;
+        Ok(())
+    }
+
+    /// Match entries by (kind, input stem, ss level); missing entries
+    /// (new inputs/levels) are the caller's responsibility to treat as
+    /// non-regressions rather than errors.
+    pub fn find(&self, kind: &str, input: &str, level: &str) -> Option<&Entry> {
+        self.entries
+            .iter()
+            .find(|e| e.kind == kind && e.input == input && e.level == level)
+    }
+}
+
+/// `(current - baseline) / baseline`; positive means the current run is
+/// slower than the baseline.
+pub fn relative_delta(current_mean: f64, baseline_mean: f64) -> f64 {
+    (current_mean - baseline_mean) / baseline_mean
+}
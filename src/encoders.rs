@@ -0,0 +1,140 @@
+use std::path::Path;
+use std::process::Command;
+
+use anyhow::{Context, Result};
+use regex::{Regex, RegexBuilder};
+use serde::Deserialize;
+
+/// Which stream the version-probe invocation prints its banner to.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProbeStream {
+    #[default]
+    Stdout,
+    Stderr,
+}
+
+/// How to extract an encoder's version string: run it with `args` and
+/// capture group 1 of `regex` against the chosen output stream.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProbeDef {
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub stream: ProbeStream,
+    pub regex: String,
+}
+
+/// A declarative description of one encoder: how to recognize its binary,
+/// how to probe its version, and the command line template to benchmark
+/// it with. `command` may reference `{encoder}`, `{input}`, `{output}`,
+/// `{threads}`, `{limit}` and `{extra}` placeholders; `{ss}` is left alone
+/// so hyperfine's own `-P ss <low> <high>` sweep expands it.
+#[derive(Debug, Clone, Deserialize)]
+pub struct EncoderDef {
+    pub name: String,
+    pub binary_pattern: String,
+    pub probe: ProbeDef,
+    pub command: String,
+    pub levels: (String, String),
+}
+
+impl EncoderDef {
+    fn matches_binary(&self, enc: &Path) -> bool {
+        let Some(file_name) = enc.file_name().and_then(|n| n.to_str()) else {
+            return false;
+        };
+        RegexBuilder::new(&self.binary_pattern)
+            .case_insensitive(true)
+            .build()
+            .map(|re| re.is_match(file_name))
+            .unwrap_or(false)
+    }
+
+    fn probe_version(&self, enc: &Path) -> Option<String> {
+        let out = Command::new(enc).args(&self.probe.args).output().ok()?;
+        let text = match self.probe.stream {
+            ProbeStream::Stdout => out.stdout,
+            ProbeStream::Stderr => out.stderr,
+        };
+        let text = std::str::from_utf8(&text).ok()?;
+        let re = Regex::new(&self.probe.regex).ok()?;
+        re.captures(text)
+            .and_then(|caps| caps.get(1))
+            .map(|m| m.as_str().to_owned())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Registry {
+    pub encoders: Vec<EncoderDef>,
+}
+
+impl Registry {
+    pub fn load(path: &Path) -> Result<Self> {
+        let data = std::fs::read_to_string(path)
+            .with_context(|| format!("reading encoder config {}", path.display()))?;
+
+        if path.extension().and_then(|e| e.to_str()) == Some("json") {
+            Ok(serde_json::from_str(&data)?)
+        } else {
+            Ok(toml::from_str(&data)?)
+        }
+    }
+
+    /// The aom/rav1e/svt definitions this crate always shipped with, now
+    /// expressed as data instead of hardcoded command builders.
+    pub fn builtin() -> Self {
+        Registry {
+            encoders: vec![
+                EncoderDef {
+                    name: "aom".into(),
+                    binary_pattern: "aomenc".into(),
+                    probe: ProbeDef {
+                        args: vec!["--help".into()],
+                        stream: ProbeStream::Stdout,
+                        regex: r"av1    - AOMedia Project AV1 Encoder (\S+) ".into(),
+                    },
+                    command: "{encoder} --tile-rows=2 --tile-columns=2 --cpu-used={ss} \
+                              --threads={threads} --limit={limit} -o {output} {input} {extra}"
+                        .into(),
+                    levels: ("0".into(), "8".into()),
+                },
+                EncoderDef {
+                    name: "rav1e".into(),
+                    binary_pattern: "rav1e".into(),
+                    probe: ProbeDef {
+                        args: vec!["--version".into()],
+                        stream: ProbeStream::Stdout,
+                        regex: r"rav1e (\S+) \((\S+)\)".into(),
+                    },
+                    command: "{encoder} -y --tiles 16 --threads {threads} -l {limit} -s {ss} \
+                              -o {output} {input} {extra}"
+                        .into(),
+                    levels: ("0".into(), "10".into()),
+                },
+                EncoderDef {
+                    name: "svt".into(),
+                    binary_pattern: "SvtAv1EncApp".into(),
+                    probe: ProbeDef {
+                        args: vec![],
+                        stream: ProbeStream::Stderr,
+                        regex: r"SVT \[version\]:	SVT-AV1 Encoder Lib (\S+)\s".into(),
+                    },
+                    command: "{encoder} --preset {ss} --tile-rows 2 --tile-columns 2 \
+                              --lp {threads} -n {limit} -b {output} -i {input} {extra}"
+                        .into(),
+                    levels: ("0".into(), "8".into()),
+                },
+            ],
+        }
+    }
+
+    /// Find the first entry whose `binary_pattern` matches `enc`'s file
+    /// name and whose version probe succeeds.
+    pub fn probe(&self, enc: &Path) -> Option<(&EncoderDef, String)> {
+        self.encoders
+            .iter()
+            .filter(|def| def.matches_binary(enc))
+            .find_map(|def| def.probe_version(enc).map(|ver| (def, ver)))
+    }
+}
@@ -0,0 +1,72 @@
+use std::fs;
+
+use anyhow::Result;
+use spreadsheet_ods::{Sheet, Value};
+
+const BOOST_PATH: &str = "/sys/devices/system/cpu/cpufreq/boost";
+
+/// Snapshot of the machine's CPU frequency-scaling state, recorded
+/// alongside the benchmark results so runs stay comparable across
+/// machines. Fields are `None` on non-Linux or when the relevant sysfs
+/// file isn't present (e.g. inside a VM without cpufreq).
+#[derive(Debug, Default)]
+pub struct Snapshot {
+    pub governor: Option<String>,
+    pub boost: Option<String>,
+}
+
+fn governor_path(cpu: &str) -> String {
+    format!("/sys/devices/system/cpu/{cpu}/cpufreq/scaling_governor")
+}
+
+/// Read cpu0's scaling governor and the system-wide turbo/boost state.
+pub fn snapshot() -> Snapshot {
+    Snapshot {
+        governor: fs::read_to_string(governor_path("cpu0"))
+            .ok()
+            .map(|s| s.trim().to_owned()),
+        boost: fs::read_to_string(BOOST_PATH)
+            .ok()
+            .map(|s| s.trim().to_owned()),
+    }
+}
+
+/// Set the scaling governor on every CPU found under
+/// `/sys/devices/system/cpu`. Requires root; writing is best-effort since
+/// the benchmark itself doesn't depend on it succeeding. Each CPU that
+/// can't be written (e.g. permission denied on a non-root run) is reported
+/// with a warning rather than aborting the rest.
+pub fn set_governor(governor: &str) -> Result<()> {
+    for entry in fs::read_dir("/sys/devices/system/cpu")? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+
+        if !name.starts_with("cpu") || !name[3..].chars().all(|c| c.is_ascii_digit()) {
+            continue;
+        }
+
+        let path = entry.path().join("cpufreq/scaling_governor");
+        if path.exists() {
+            if let Err(e) = fs::write(&path, governor) {
+                eprintln!("warning: failed to set governor via {}: {e}", path.display());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Render the snapshot as a one-row metadata sheet for the `WorkBook`.
+pub fn metadata_sheet(snapshot: &Snapshot) -> Sheet {
+    let mut s = Sheet::new("environment");
+    s.set_value(0, 0, Value::from("governor"));
+    s.set_value(0, 1, Value::from("boost"));
+    s.set_value(
+        1,
+        0,
+        Value::from(snapshot.governor.as_deref().unwrap_or("")),
+    );
+    s.set_value(1, 1, Value::from(snapshot.boost.as_deref().unwrap_or("")));
+    s
+}
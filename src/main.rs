@@ -1,15 +1,27 @@
-use std::ffi::OsStr;
 use std::fs::File;
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Command, Stdio};
 
 use anyhow::Result;
 use clap::Parser;
 use icu_locid::locale;
 use platform_info::*;
-use regex::{Regex, RegexBuilder};
+use rayon::prelude::*;
 use spreadsheet_ods::{Sheet, Value, WorkBook};
 
+mod baseline;
+mod encoders;
+mod environment;
+mod progress;
+mod quality;
+
+/// Default `jobs`: fit as many `threads`-wide benchmarks as the machine's
+/// cores allow, so lowering `--threads` raises the default concurrency to
+/// match instead of leaving cores idle.
+fn default_jobs(threads: usize) -> usize {
+    (num_cpus::get() / threads.max(1)).max(1)
+}
+
 fn default_tag() -> String {
     let pi = PlatformInfo::new().unwrap();
 
@@ -20,11 +32,11 @@ fn default_tag() -> String {
     )
 }
 
-#[derive(Debug)]
-enum EncoderVersion {
-    Aom(String),
-    Rav1e(String),
-    Svt(String),
+/// Parse a `--extra NAME=ARGS` value into (encoder name, extra args).
+fn parse_extra(s: &str) -> std::result::Result<(String, String), String> {
+    s.split_once('=')
+        .map(|(name, args)| (name.to_owned(), args.to_owned()))
+        .ok_or_else(|| format!("expected NAME=ARGS, got `{s}`"))
 }
 
 #[derive(Debug, Parser)]
@@ -58,110 +70,112 @@ struct Opt {
     /// Set the threadpool size
     #[arg(long, default_value = "16")]
     threads: usize,
-    /// Extra command for the aom instances
-    #[arg(long, default_value = "", env = "EXTRA_AOM")]
-    extra_aom: String,
-    /// Extra command for the rav1e instances
-    #[arg(long, default_value = "", env = "EXTRA_RAV1E")]
-    extra_rav1e: String,
-    /// Extra command for the svt-av1 instances
-    #[arg(long, default_value = "", env = "EXTRA_SVT")]
-    extra_svt: String,
+    /// Number of (input, encoder) benchmarks to run concurrently. Defaults
+    /// to fitting `threads`-wide jobs within the machine's core count; keep
+    /// `jobs * threads` within that count, or the runs will contend for
+    /// CPU and the timings stop being meaningful
+    #[arg(long)]
+    jobs: Option<usize>,
+    /// Extra arguments for a given encoder, as `NAME=ARGS` (e.g.
+    /// `aom=--row-mt=1`); `NAME` matches an encoder name from the
+    /// registry. Repeatable, one per encoder
+    #[arg(long = "extra", value_parser = parse_extra)]
+    extras: Vec<(String, String)>,
+    /// Load encoder definitions (binary pattern, version probe, command
+    /// template, speed-level range) from a TOML/JSON config file instead
+    /// of the built-in aom/rav1e/svt definitions
+    #[arg(long)]
+    encoder_config: Option<PathBuf>,
     /// Use the provided runner to execute the encoder
     #[arg(long, default_value = "", env = "RUNNER_COMMAND")]
     runner: String,
-}
-
-fn aom_version<P: AsRef<OsStr>>(enc: P) -> Option<EncoderVersion> {
-    let out = Command::new(enc)
-        .arg("--help")
-        .output()
-        .expect("cannot run the encoder");
-
-    std::str::from_utf8(&out.stdout).ok().and_then(|out| {
-        Regex::new(r"av1    - AOMedia Project AV1 Encoder (\S+) ")
-            .ok()
-            .and_then(|re| {
-                re.captures(out)
-                    .and_then(|caps| caps.get(1))
-                    .map(|ver| EncoderVersion::Aom(ver.as_str().to_owned()))
-            })
-    })
-}
-
-fn rav1e_y_option<P: AsRef<Path>>(enc: P) -> bool {
-    let out = Command::new(enc.as_ref())
-        .arg("--help")
-        .output()
-        .expect("cannot run the encoder");
-
-    std::str::from_utf8(&out.stdout)
-        .ok()
-        .and_then(|out| {
-            RegexBuilder::new(r"\s*-y")
-                .multi_line(true)
-                .build()
-                .ok()
-                .and_then(|re| {
-                    let v = re.is_match(out);
-                    Some(v)
-                })
-        })
-        .unwrap_or(false)
-}
-
-fn rav1e_version<P: AsRef<OsStr>>(enc: P) -> Option<EncoderVersion> {
-    let out = Command::new(enc)
-        .arg("--version")
-        .output()
-        .expect("cannot run the encoder");
-
-    std::str::from_utf8(&out.stdout).ok().and_then(|out| {
-        Regex::new(r"rav1e (\S+) \((\S+)\)").ok().and_then(|re| {
-            re.captures(out)
-                .and_then(|caps| {
-                    let nominal = caps.get(1);
-                    let specific = caps.get(2);
-                    if let (Some(nominal), Some(specific)) = (nominal, specific) {
-                        Some(if specific.as_str() == "UNKNOWN" {
-                            nominal
-                        } else {
-                            specific
-                        })
-                    } else {
-                        nominal
-                    }
-                })
-                .map(|ver| EncoderVersion::Rav1e(ver.as_str().to_owned()))
-        })
-    })
-}
-
-fn svt_version<P: AsRef<OsStr>>(enc: P) -> Option<EncoderVersion> {
-    let out = Command::new(enc).output().expect("cannot run the encoder");
-    std::str::from_utf8(&out.stderr).ok().and_then(|out| {
-        Regex::new(r"SVT \[version\]:	SVT-AV1 Encoder Lib (\S+)\s")
-            .ok()
-            .and_then(|re| {
-                re.captures(out)
-                    .and_then(|caps| caps.get(1))
-                    .map(|ver| EncoderVersion::Svt(ver.as_str().to_owned()))
-            })
-    })
-}
-
-fn probe_version<P: AsRef<OsStr>>(enc: P) -> Option<EncoderVersion> {
-    aom_version(&enc).or_else(|| rav1e_version(&enc).or_else(|| svt_version(&enc)))
+    /// Score each encoded speed level against its source with ffmpeg's
+    /// libvmaf filter (mean VMAF/PSNR/SSIM), adding them as extra columns
+    #[arg(long)]
+    quality: bool,
+    /// Path to a VMAF model file to pass to libvmaf (uses its built-in
+    /// default model when unset)
+    #[arg(long)]
+    vmaf_model: Option<PathBuf>,
+    /// Frame rate of the inputs, used to align the encoded and source
+    /// streams before scoring
+    #[arg(long, default_value = "25")]
+    fps: f64,
+    /// Load a baseline saved by --save-baseline and add a delta-% column
+    /// comparing each speed level's mean time against it
+    #[arg(long)]
+    baseline: Option<PathBuf>,
+    /// Save this run's (kind, input, level) mean/stddev timings to a JSON
+    /// baseline file for future --baseline comparisons
+    #[arg(long)]
+    save_baseline: Option<PathBuf>,
+    /// Fail with a non-zero exit code if any speed level is slower than
+    /// its --baseline entry by more than this fraction
+    #[arg(long, default_value = "0.05")]
+    regression_threshold: f64,
+    /// Number of untimed warmup runs hyperfine performs before timing,
+    /// so the first timed run isn't paying cold-cache costs
+    #[arg(long)]
+    warmup: Option<usize>,
+    /// Shell command hyperfine runs before each timed run
+    #[arg(long)]
+    prepare: Option<String>,
+    /// Shell command hyperfine runs after each timed run
+    #[arg(long)]
+    cleanup: Option<String>,
+    /// Pin the encoder invocation to this cpuset via `taskset -c <cpuset>`
+    /// (Linux only), removing scheduler migration jitter from the timings
+    #[arg(long)]
+    pin_cores: Option<String>,
+    /// Set this scaling governor (e.g. `performance`) on every CPU before
+    /// benchmarking (requires running privileged). The governor and
+    /// turbo/boost state are recorded into the spreadsheet's metadata row
+    /// on every run, with or without this flag
+    #[arg(long)]
+    governor: Option<String>,
 }
 
 impl Opt {
-    fn hyperfine(&self, cmd: &str, levels: (&str, &str), out_name: String) -> Result<Sheet> {
+    /// Extra arguments configured for the given encoder name via `--extra`.
+    fn extra_for(&self, name: &str) -> &str {
+        self.extras
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, args)| args.as_str())
+            .unwrap_or("")
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn hyperfine(
+        &self,
+        cmd: &str,
+        levels: (&str, &str),
+        out_name: String,
+        outfile: &Path,
+        infile: &Path,
+        kind: &str,
+        ver: &str,
+        baseline: Option<&baseline::Baseline>,
+        progress: Option<&indicatif::ProgressBar>,
+    ) -> Result<(Sheet, Vec<baseline::Entry>, bool)> {
         let mut hf = Command::new("hyperfine");
 
         hf.arg("-r").arg(&self.runs);
         if self.show_output {
             hf.arg("--show-output");
         }
+        if progress.is_some() {
+            hf.stdout(Stdio::piped());
+        }
+        if let Some(warmup) = self.warmup {
+            hf.arg("--warmup").arg(warmup.to_string());
+        }
+        if let Some(prepare) = &self.prepare {
+            hf.arg("--prepare").arg(prepare);
+        }
+        if let Some(cleanup) = &self.cleanup {
+            hf.arg("--cleanup").arg(cleanup);
+        }
         let csv_export = format!("{}.csv", out_name);
         let json_export = format!("{}.json", out_name);
         let md_export = format!("{}.md", out_name);
@@ -178,6 +192,8 @@ impl Opt {
 
         let mut child = child.spawn().expect("hyperfine failed");
 
+        progress::drive(&mut child, progress);
+
         //        std::io::stdout().write_all(&output.stdout).unwrap();
         //        std::io::stderr().write_all(&output.stderr).unwrap();
         child.wait().expect("failed to wait on hyperfine");
@@ -186,9 +202,23 @@ impl Opt {
         let f = File::open(&csv_export)?;
         // Save the header as well.
         let mut r = csv::ReaderBuilder::new().has_headers(false).from_reader(f);
-        for (x, res) in r.records().enumerate() {
-            let record = res?;
+        let records = r.records().collect::<std::result::Result<Vec<_>, _>>()?;
+
+        let mut ss_col = None;
+        let mut mean_col = None;
+        let mut stddev_col = None;
+        for (x, record) in records.iter().enumerate() {
             for (y, cell) in record.iter().enumerate() {
+                if x == 0 {
+                    match cell {
+                        // hyperfine's --export-csv prefixes `-P` parameter
+                        // columns with `parameter_`.
+                        "parameter_ss" => ss_col = Some(y),
+                        "mean" => mean_col = Some(y),
+                        "stddev" => stddev_col = Some(y),
+                        _ => {}
+                    }
+                }
                 let val = if let Ok(v) = cell.parse::<f64>() {
                     Value::from(v)
                 } else {
@@ -198,7 +228,159 @@ impl Opt {
             }
         }
 
-        Ok(s)
+        // Columns appended by the optional passes below all start past the
+        // CSV's own columns, and each pass advances the cursor past what it
+        // wrote so they never collide.
+        let mut next_col = records.first().map_or(0, |h| h.len() as u32);
+
+        if self.quality {
+            self.append_quality_columns(&mut s, &records, ss_col, outfile, infile, &mut next_col);
+        }
+
+        let (entries, regression) = if self.baseline.is_some() || self.save_baseline.is_some() {
+            self.append_baseline_columns(
+                &mut s, &records, ss_col, mean_col, stddev_col, kind, ver, infile, baseline,
+                &mut next_col,
+            )
+        } else {
+            (Vec::new(), false)
+        };
+
+        Ok((s, entries, regression))
+    }
+
+    /// Walk the `.ivf` files produced for each `ss` level and score them
+    /// against `infile` with `quality::score`, adding `vmaf`/`psnr`/`ssim`
+    /// columns to `sheet`. Levels that can't be scored (missing ffmpeg,
+    /// non-zero exit, unparsable log) are left with empty cells.
+    #[allow(clippy::too_many_arguments)]
+    fn append_quality_columns(
+        &self,
+        sheet: &mut Sheet,
+        records: &[csv::StringRecord],
+        ss_col: Option<usize>,
+        outfile: &Path,
+        infile: &Path,
+        next_col: &mut u32,
+    ) {
+        let Some(ss_col) = ss_col else {
+            return;
+        };
+
+        let col = *next_col;
+        sheet.set_value(0, col, Value::from("vmaf"));
+        sheet.set_value(0, col + 1, Value::from("psnr"));
+        sheet.set_value(0, col + 2, Value::from("ssim"));
+
+        for (x, record) in records.iter().enumerate().skip(1) {
+            let Some(ss) = record.get(ss_col) else {
+                continue;
+            };
+            let encoded = PathBuf::from(outfile.display().to_string().replace("{ss}", ss));
+
+            if let Some(scores) = quality::score(
+                self.vmaf_model.as_deref(),
+                self.fps,
+                self.limit,
+                &encoded,
+                infile,
+            ) {
+                if let Some(vmaf) = scores.vmaf {
+                    sheet.set_value(x as u32, col, Value::from(vmaf));
+                }
+                if let Some(psnr) = scores.psnr {
+                    sheet.set_value(x as u32, col + 1, Value::from(psnr));
+                }
+                if let Some(ssim) = scores.ssim {
+                    sheet.set_value(x as u32, col + 2, Value::from(ssim));
+                }
+            }
+        }
+
+        *next_col += 3;
+    }
+
+    /// Record a `baseline::Entry` per speed level (for `--save-baseline`)
+    /// and, when `baseline` is supplied, add `baseline_mean`/`delta_pct`
+    /// columns comparing this run's mean time against it. Returns the
+    /// recorded entries and whether any level regressed past
+    /// `--regression-threshold`. Levels missing from `baseline` are left
+    /// with an empty delta rather than treated as a regression.
+    #[allow(clippy::too_many_arguments)]
+    fn append_baseline_columns(
+        &self,
+        sheet: &mut Sheet,
+        records: &[csv::StringRecord],
+        ss_col: Option<usize>,
+        mean_col: Option<usize>,
+        stddev_col: Option<usize>,
+        kind: &str,
+        ver: &str,
+        infile: &Path,
+        baseline: Option<&baseline::Baseline>,
+        next_col: &mut u32,
+    ) -> (Vec<baseline::Entry>, bool) {
+        let (Some(ss_col), Some(mean_col), Some(stddev_col)) = (ss_col, mean_col, stddev_col)
+        else {
+            eprintln!(
+                "warning: hyperfine's CSV export is missing the ss/mean/stddev columns; \
+                 skipping baseline comparison for {kind}"
+            );
+            return (Vec::new(), false);
+        };
+
+        let input = infile
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or_default()
+            .to_owned();
+
+        let col = *next_col;
+        if baseline.is_some() {
+            sheet.set_value(0, col, Value::from("baseline_mean"));
+            sheet.set_value(0, col + 1, Value::from("delta_pct"));
+            *next_col += 2;
+        }
+
+        let mut entries = Vec::new();
+        let mut regression = false;
+
+        for (x, record) in records.iter().enumerate().skip(1) {
+            let (Some(level), Some(mean), Some(stddev)) = (
+                record.get(ss_col),
+                record.get(mean_col).and_then(|v| v.parse::<f64>().ok()),
+                record.get(stddev_col).and_then(|v| v.parse::<f64>().ok()),
+            ) else {
+                continue;
+            };
+
+            entries.push(baseline::Entry {
+                kind: kind.to_owned(),
+                version: ver.to_owned(),
+                input: input.clone(),
+                level: level.to_owned(),
+                mean,
+                stddev,
+            });
+
+            if let Some(entry) = baseline.and_then(|b| b.find(kind, &input, level)) {
+                let delta = baseline::relative_delta(mean, entry.mean);
+                sheet.set_value(x as u32, col, Value::from(entry.mean));
+                sheet.set_value(x as u32, col + 1, Value::from(delta * 100.0));
+
+                if delta > self.regression_threshold {
+                    regression = true;
+                    println!(
+                        "regression: {kind} {input} ss={level} is {:.1}% slower than baseline ({:.3}s -> {:.3}s)",
+                        delta * 100.0,
+                        entry.mean,
+                        mean,
+                    );
+                }
+            }
+        }
+
+        (entries, regression)
     }
 
     fn outfiles<P: AsRef<Path>>(&self, infile: P, ver: &str, kind: &str) -> (PathBuf, String) {
@@ -219,56 +401,60 @@ impl Opt {
         (outfile, stats_file)
     }
 
-    fn aom_command<P: AsRef<Path>>(&self, enc: P, infile: P, ver: &str) -> Result<Sheet> {
-        let (outfile, stats_file) = self.outfiles(&infile, ver, "aom");
-
-        let run = format!("{} {} --tile-rows=2 --tile-columns=2 --cpu-used={{ss}} --threads={} --limit={} -o {} {} {}",
-            self.runner,
-            enc.as_ref().display(),
-            self.threads,
-            self.limit,
-            outfile.display(),
-            infile.as_ref().display(),
-            &self.extra_aom,
+    /// Drive `hyperfine` from an `encoders::EncoderDef`'s command
+    /// template, expanding every placeholder but `{ss}` (hyperfine's own
+    /// `-P` sweep substitutes that one).
+    #[allow(clippy::too_many_arguments)]
+    fn encoder_command<P: AsRef<Path>>(
+        &self,
+        def: &encoders::EncoderDef,
+        enc: P,
+        infile: P,
+        ver: &str,
+        baseline: Option<&baseline::Baseline>,
+        progress: &progress::Progress,
+    ) -> Result<(Sheet, Vec<baseline::Entry>, bool)> {
+        let (outfile, stats_file) = self.outfiles(&infile, ver, &def.name);
+
+        let expanded = def
+            .command
+            .replace("{encoder}", &enc.as_ref().display().to_string())
+            .replace("{threads}", &self.threads.to_string())
+            .replace("{limit}", &self.limit.to_string())
+            .replace("{input}", &infile.as_ref().display().to_string())
+            .replace("{output}", &outfile.display().to_string())
+            .replace("{extra}", self.extra_for(&def.name));
+
+        let run = match &self.pin_cores {
+            Some(cpuset) => format!("{} taskset -c {} {}", self.runner, cpuset, expanded),
+            None => format!("{} {}", self.runner, expanded),
+        };
+
+        let name = infile.as_ref().file_stem().and_then(|s| s.to_str());
+        let label = format!("{} {} ({})", def.name, ver, name.unwrap_or("?"));
+        let levels = def
+            .levels
+            .0
+            .parse::<u64>()
+            .and_then(|lo| def.levels.1.parse::<u64>().map(|hi| hi - lo + 1))
+            .unwrap_or(1);
+        let bar = progress.start_job(&label, levels);
+
+        let result = self.hyperfine(
+            &run,
+            (&def.levels.0, &def.levels.1),
+            stats_file,
+            &outfile,
+            infile.as_ref(),
+            &def.name,
+            ver,
+            baseline,
+            bar.as_ref(),
         );
 
-        self.hyperfine(&run, ("0", "8"), stats_file)
-    }
-
-    fn rav1e_command<P: AsRef<Path>>(&self, enc: P, infile: P, ver: &str) -> Result<Sheet> {
-        let (outfile, stats_file) = self.outfiles(&infile, ver, "rav1e");
-
-        let overwrite = if rav1e_y_option(&enc) { "-y" } else { "" };
-
-        let run = format!(
-            "{} {} --tiles 16 --threads {} -l {} -s {{ss}} -o {} {} {} {}",
-            self.runner,
-            enc.as_ref().display(),
-            self.threads,
-            self.limit,
-            outfile.display(),
-            infile.as_ref().display(),
-            overwrite,
-            &self.extra_rav1e,
-        );
+        progress.finish_job(bar);
 
-        self.hyperfine(&run, ("0", "10"), stats_file)
-    }
-    fn svt_command<P: AsRef<Path>>(&self, enc: P, infile: P, ver: &str) -> Result<Sheet> {
-        let (outfile, stats_file) = self.outfiles(&infile, ver, "svt");
-
-        let run = format!(
-            "{} {} --preset {{ss}} --tile-rows 2 --tile-columns 2 --lp {} -n {} -b {} -i {} {}",
-            self.runner,
-            enc.as_ref().display(),
-            self.threads,
-            self.limit,
-            outfile.display(),
-            infile.as_ref().display(),
-            &self.extra_svt,
-        );
-
-        self.hyperfine(&run, ("0", "8"), stats_file)
+        result
     }
 }
 
@@ -285,22 +471,81 @@ fn main() -> Result<()> {
 
     std::fs::create_dir_all(outdir)?;
 
+    if let Some(governor) = &opt.governor {
+        if let Err(e) = environment::set_governor(governor) {
+            eprintln!("warning: failed to set CPU governor: {e}");
+        }
+    }
+    let environment_snapshot = environment::snapshot();
+
+    let baseline = opt
+        .baseline
+        .as_deref()
+        .map(baseline::Baseline::load)
+        .transpose()?;
+
+    let registry = opt
+        .encoder_config
+        .as_deref()
+        .map(encoders::Registry::load)
+        .transpose()?
+        .unwrap_or_else(encoders::Registry::builtin);
+
+    let combos: Vec<(&PathBuf, &PathBuf)> = opt
+        .infiles
+        .iter()
+        .flat_map(|input| opt.encoders.iter().map(move |enc| (input, enc)))
+        .collect();
+
+    let jobs = opt.jobs.unwrap_or_else(|| default_jobs(opt.threads));
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(jobs)
+        .build()?;
+
+    let progress = progress::Progress::new(opt.show_output, combos.len() as u64);
+
+    // Run the (input, encoder) combinations with bounded concurrency, then
+    // push the sheets in the same deterministic order they were scheduled
+    // in, regardless of which job happened to finish first.
+    let results: Vec<Result<(Sheet, Vec<baseline::Entry>, bool)>> = pool.install(|| {
+        combos
+            .par_iter()
+            .map(|&(input, enc)| {
+                let (def, ver) = registry.probe(enc).expect("Cannot probe the encoder");
+                opt.encoder_command(def, enc, input, &ver, baseline.as_ref(), &progress)
+            })
+            .collect()
+    });
+
     let mut wb = WorkBook::new(locale!("en_US"));
-    for input in &opt.infiles {
-        for enc in &opt.encoders {
-            use self::EncoderVersion::*;
-            let s = match probe_version(enc).expect("Cannot probe the encoder") {
-                Aom(ver) => opt.aom_command(enc, input, &ver)?,
-                Rav1e(ver) => opt.rav1e_command(enc, input, &ver)?,
-                Svt(ver) => opt.svt_command(enc, input, &ver)?,
-            };
-            wb.push_sheet(s);
+    wb.push_sheet(environment::metadata_sheet(&environment_snapshot));
+    let mut saved_entries = Vec::new();
+    let mut any_regression = false;
+    for r in results {
+        let (sheet, entries, regression) = r?;
+        saved_entries.extend(entries);
+        any_regression |= regression;
+        wb.push_sheet(sheet);
+    }
+
+    if let Some(save_baseline) = &opt.save_baseline {
+        baseline::Baseline {
+            entries: saved_entries,
         }
+        .save(save_baseline)?;
     }
 
     if let Some(outname) = opt.outname {
         spreadsheet_ods::write_ods(&mut wb, outname)?;
     }
 
+    if any_regression {
+        eprintln!(
+            "one or more speed levels regressed by more than {:.1}% against the baseline",
+            opt.regression_threshold * 100.0
+        );
+        std::process::exit(1);
+    }
+
     Ok(())
 }
@@ -0,0 +1,86 @@
+use std::io::{BufRead, BufReader, IsTerminal};
+use std::process::Child;
+
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+
+/// Whether the live progress UI should be shown: only on a real terminal,
+/// and never alongside `--show-output`, since carriage-return bars would
+/// corrupt either piped logs or the encoder's own interleaved output.
+fn enabled(show_output: bool) -> bool {
+    !show_output && std::io::stdout().is_terminal()
+}
+
+/// Progress bars for one full run: a top-level bar over (input, encoder)
+/// jobs, plus one per-job bar handed out for each speed-level sweep.
+/// Every method is a no-op when the UI is disabled, so callers don't need
+/// to branch on whether progress reporting is active.
+pub struct Progress {
+    multi: Option<MultiProgress>,
+    top: Option<ProgressBar>,
+}
+
+impl Progress {
+    pub fn new(show_output: bool, total_jobs: u64) -> Self {
+        if !enabled(show_output) {
+            return Progress {
+                multi: None,
+                top: None,
+            };
+        }
+
+        let multi = MultiProgress::new();
+        let top = multi.add(ProgressBar::new(total_jobs));
+        top.set_style(
+            ProgressStyle::with_template("jobs [{bar:40}] {pos}/{len} (eta {eta})")
+                .unwrap()
+                .progress_chars("=> "),
+        );
+
+        Progress {
+            multi: Some(multi),
+            top: Some(top),
+        }
+    }
+
+    /// Start a bar tracking one (input, encoder) job's speed-level sweep.
+    pub fn start_job(&self, label: &str, levels: u64) -> Option<ProgressBar> {
+        let multi = self.multi.as_ref()?;
+        let bar = multi.add(ProgressBar::new(levels));
+        bar.set_style(
+            ProgressStyle::with_template("  {msg} [{bar:30}] {pos}/{len} levels (eta {eta})")
+                .unwrap()
+                .progress_chars("=> "),
+        );
+        bar.set_message(label.to_owned());
+        Some(bar)
+    }
+
+    /// Clear a job's bar and advance the top-level one.
+    pub fn finish_job(&self, bar: Option<ProgressBar>) {
+        if let Some(bar) = bar {
+            bar.finish_and_clear();
+        }
+        if let Some(top) = &self.top {
+            top.inc(1);
+        }
+    }
+}
+
+/// Advance `bar` by one tick for every `Benchmark N: ...` header
+/// hyperfine prints to stdout, one per speed level in the sweep. Blocks
+/// until the child's stdout is closed, so callers should still `wait()`
+/// the child afterwards to reap it.
+pub fn drive(child: &mut Child, bar: Option<&ProgressBar>) {
+    let Some(bar) = bar else {
+        return;
+    };
+    let Some(stdout) = child.stdout.take() else {
+        return;
+    };
+
+    for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+        if line.starts_with("Benchmark ") {
+            bar.inc(1);
+        }
+    }
+}
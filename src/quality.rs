@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::Command;
+
+use serde::Deserialize;
+
+/// Mean pooled scores for one encoded/source pair, as reported by `libvmaf`.
+/// Each field is independently `None` if its metric is missing from the
+/// log, so one absent metric doesn't blank the others.
+#[derive(Debug)]
+pub struct QualityScores {
+    pub vmaf: Option<f64>,
+    pub psnr: Option<f64>,
+    pub ssim: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PooledMetric {
+    mean: f64,
+}
+
+#[derive(Debug, Deserialize)]
+struct VmafLog {
+    pooled_metrics: HashMap<String, PooledMetric>,
+}
+
+/// Score `encoded` against `source` with ffmpeg's `libvmaf` filter, returning
+/// the mean VMAF/PSNR/SSIM over the first `limit` frames.
+///
+/// Returns `None` whenever the score can't be trusted: ffmpeg is missing,
+/// the filter run exits non-zero (e.g. mismatched resolution/pixel format),
+/// or the VMAF log can't be parsed. Callers should treat `None` as "leave
+/// the cell empty" rather than a hard error.
+pub fn score(
+    vmaf_model: Option<&Path>,
+    fps: f64,
+    limit: usize,
+    encoded: &Path,
+    source: &Path,
+) -> Option<QualityScores> {
+    let log_path = encoded.with_extension("vmaf.json");
+
+    let model_opt = vmaf_model
+        .map(|p| format!(":model_path={}", p.display()))
+        .unwrap_or_default();
+
+    let lavfi = format!(
+        "[0:v]setpts=PTS-STARTPTS[d];[1:v]setpts=PTS-STARTPTS[r];[d][r]libvmaf=feature=name=psnr|name=float_ssim:log_fmt=json:log_path={}{}",
+        log_path.display(),
+        model_opt,
+    );
+
+    let fps = fps.to_string();
+    let limit = limit.to_string();
+
+    let out = Command::new("ffmpeg")
+        .args(["-r", &fps, "-i"])
+        .arg(encoded)
+        .args(["-r", &fps, "-i"])
+        .arg(source)
+        .args(["-lavfi", &lavfi])
+        .args(["-frames:v", &limit])
+        .args(["-f", "null", "-"])
+        .output()
+        .ok()?;
+
+    if !out.status.success() {
+        return None;
+    }
+
+    let log = std::fs::read_to_string(&log_path).ok()?;
+    let log: VmafLog = serde_json::from_str(&log).ok()?;
+
+    // libvmaf emits PSNR per plane (`psnr_y`/`psnr_cb`/`psnr_cr`); only the
+    // luma plane is read here. Each lookup is independent so a metric
+    // missing from the log leaves just that column empty.
+    Some(QualityScores {
+        vmaf: log.pooled_metrics.get("vmaf").map(|m| m.mean),
+        psnr: log.pooled_metrics.get("psnr_y").map(|m| m.mean),
+        ssim: log.pooled_metrics.get("float_ssim").map(|m| m.mean),
+    })
+}